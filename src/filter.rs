@@ -0,0 +1,106 @@
+//! Env-logger-style module filtering, used by `LogBuilder::filter`.
+
+use log::{LogLevel, LogLevelFilter};
+
+/// A single `module::path=level` directive parsed out of a filter spec.
+struct Directive {
+    name: String,
+    level: LogLevelFilter,
+}
+
+/// A compiled filter spec, ready to be checked against individual records on
+/// the hot path without any further allocation.
+pub struct Filter {
+    directives: Vec<Directive>,
+    default: LogLevelFilter,
+}
+
+impl Filter {
+    /// Parses an env_logger-style filter spec, e.g.
+    /// `"debug,hello::crate=trace,noisy_mod=off"`, into a `Filter`.
+    ///
+    /// A bare level with no `=` sets the default level applied to modules
+    /// which match no directive. Unparsable directives are ignored.
+    pub fn new(spec: &str) -> Filter {
+        let mut directives = Vec::new();
+        let mut default = LogLevelFilter::max();
+
+        for part in spec.split(',') {
+            let part = part.trim();
+            if part.is_empty() {
+                continue;
+            }
+
+            let mut iter = part.splitn(2, '=');
+            let name = iter.next().unwrap();
+            match iter.next() {
+                Some(level) => {
+                    if let Some(level) = parse_level(level) {
+                        directives.push(Directive { name: name.to_owned(), level: level });
+                    }
+                }
+                None => {
+                    match parse_level(name) {
+                        Some(level) => default = level,
+                        None => directives.push(Directive {
+                            name: name.to_owned(),
+                            level: LogLevelFilter::max(),
+                        }),
+                    }
+                }
+            }
+        }
+
+        // Longest prefix first, so a more specific directive always wins
+        // over a shorter one that also matches.
+        directives.sort_by(|a, b| b.name.len().cmp(&a.name.len()));
+
+        Filter {
+            directives: directives,
+            default: default,
+        }
+    }
+
+    /// Returns `true` if a record at `level` from `module_path` should be
+    /// logged.
+    pub fn enabled(&self, level: LogLevel, module_path: &str) -> bool {
+        for directive in &self.directives {
+            if module_path.starts_with(directive.name.as_str()) {
+                return level <= directive.level;
+            }
+        }
+
+        level <= self.default
+    }
+
+    /// Returns `true` if this is the no-op filter that allows everything
+    /// through: no directives and a default of `trace`. That is the case
+    /// when `LogBuilder::filter` was never called, and also for a spec such
+    /// as `""` or `"trace"`.
+    pub fn is_unset(&self) -> bool {
+        self.directives.is_empty() && self.default == LogLevelFilter::max()
+    }
+}
+
+impl Default for Filter {
+    /// An empty filter which allows every record through, matching the
+    /// logger's behaviour before filtering was added.
+    fn default() -> Filter {
+        Filter {
+            directives: Vec::new(),
+            default: LogLevelFilter::max(),
+        }
+    }
+}
+
+fn parse_level(s: &str) -> Option<LogLevelFilter> {
+    match s.trim().to_lowercase().as_str() {
+        "off" => Some(LogLevelFilter::Off),
+        "error" => Some(LogLevelFilter::Error),
+        "warn" => Some(LogLevelFilter::Warn),
+        "info" => Some(LogLevelFilter::Info),
+        "debug" => Some(LogLevelFilter::Debug),
+        "trace" => Some(LogLevelFilter::Trace),
+        _ => None,
+    }
+}
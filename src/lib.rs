@@ -1,5 +1,8 @@
-//! A logger which writes to the Android logging subsystem. It must be compiled
-//! with the Android NDK in order to link to `liblog`.
+//! A logger which writes to the Android logging subsystem when built for
+//! Android (requiring the Android NDK in order to link to `liblog`), and
+//! falls back to writing to stderr, honoring `RUST_LOG`, everywhere else.
+//! This lets downstream crates call the same `init` on-device and in host
+//! unit tests or desktop development builds.
 //!
 //! ## Example
 //!
@@ -27,19 +30,164 @@
 //! 12-25 12:00:00.000  1234  1234 E MyApp: Nothing more to say
 
 extern crate log;
+#[cfg(target_os = "android")]
 extern crate android_liblog_sys;
 
+mod filter;
+
+use std::borrow::Cow;
 use std::ffi::CString;
+#[cfg(not(target_os = "android"))]
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use log::{Log, LogLevel, LogLevelFilter, LogMetadata, LogRecord, SetLoggerError};
-use android_liblog_sys::{__android_log_write, LogPriority};
+#[cfg(target_os = "android")]
+use android_liblog_sys::{__android_log_write, __android_log_buf_write, LogPriority};
+
+use filter::Filter;
+
+/// Tracks whether the global logger has already been installed by
+/// `init_once`/`LogBuilder::init_once`.
+static INIT_ONCE: AtomicBool = AtomicBool::new(false);
+
+/// Selects which logcat buffer a record is written to; see
+/// `LogBuilder::buffer`. Only defined when building for Android: off
+/// Android neither this type nor `LogBuilder::buffer` exists; see the
+/// crate-level docs for the host fallback.
+#[cfg(target_os = "android")]
+#[derive(Clone, Copy)]
+pub enum Buffer {
+    /// The default buffer, i.e. `logcat` with no `-b` flag.
+    Main,
+    /// The buffer used for radio/telephony messages, i.e. `logcat -b radio`.
+    Radio,
+    /// The buffer used for binary event messages, i.e. `logcat -b events`.
+    Events,
+    /// The buffer used for system/service messages, i.e. `logcat -b system`.
+    System,
+    /// The buffer used for crash dumps, i.e. `logcat -b crash`.
+    Crash,
+}
+
+#[cfg(target_os = "android")]
+impl Buffer {
+    fn id(&self) -> i32 {
+        match *self {
+            Buffer::Main => 0,
+            Buffer::Radio => 1,
+            Buffer::Events => 2,
+            Buffer::System => 3,
+            Buffer::Crash => 4,
+        }
+    }
+}
+
+/// liblog truncates any single log entry at roughly this many bytes,
+/// including the tag and null terminators, so messages longer than this are
+/// split across multiple entries; see `ChunkSize`.
+#[cfg(target_os = "android")]
+const LOGGER_ENTRY_MAX_LEN: usize = 4000;
+
+/// Bytes of `LOGGER_ENTRY_MAX_LEN` reserved for the tag and message null
+/// terminators when computing a chunk budget from the tag length.
+#[cfg(target_os = "android")]
+const CHUNK_OVERHEAD: usize = 2;
+
+/// Controls how `AndroidLogger` splits messages that are too long for a
+/// single `liblog` entry; see `LogBuilder::chunk_size`. Only defined when
+/// building for Android, where liblog imposes the limit in the first place:
+/// off Android neither this type nor `LogBuilder::chunk_size` exists.
+#[cfg(target_os = "android")]
+pub enum ChunkSize {
+    /// Compute a safe chunk size from the tag length (the default).
+    Auto,
+    /// Use a fixed chunk size, in bytes.
+    Fixed(usize),
+    /// Never split messages, even if `liblog` ends up truncating them.
+    Disabled,
+}
+
+#[cfg(target_os = "android")]
+impl ChunkSize {
+    fn resolve(&self, tag_len: usize) -> usize {
+        match *self {
+            ChunkSize::Auto => LOGGER_ENTRY_MAX_LEN.saturating_sub(tag_len + CHUNK_OVERHEAD),
+            ChunkSize::Fixed(len) => len,
+            ChunkSize::Disabled => usize::max_value(),
+        }
+    }
+}
+
+/// Splits `message` into chunks of at most `max_len` bytes, breaking on
+/// UTF-8 char boundaries and preferring the last newline within the budget
+/// so multi-line output stays readable.
+#[cfg(target_os = "android")]
+fn split_message(message: &str, max_len: usize) -> Vec<&str> {
+    let max_len = max_len.max(1);
+
+    let mut chunks = Vec::new();
+    let mut rest = message;
+
+    while rest.len() > max_len {
+        let mut split_at = max_len;
+        while split_at > 0 && !rest.is_char_boundary(split_at) {
+            split_at -= 1;
+        }
+        // Always make forward progress, even if a single char is wider
+        // than the budget.
+        if split_at == 0 {
+            split_at = rest.char_indices().nth(1).map_or(rest.len(), |(i, _)| i);
+        }
+
+        match rest[..split_at].rfind('\n') {
+            Some(newline) => {
+                chunks.push(&rest[..newline]);
+                rest = &rest[newline + 1..];
+            }
+            None => {
+                chunks.push(&rest[..split_at]);
+                rest = &rest[split_at..];
+            }
+        }
+    }
+
+    chunks.push(rest);
+    chunks
+}
+
+/// Appends `pairs` to `message` as `key=value` tokens, quoting any value
+/// that contains whitespace so the result stays easy for tooling to parse
+/// back out while leaving the human-readable message untouched.
+fn append_kv(mut message: String, pairs: &[(String, String)]) -> String {
+    for &(ref key, ref value) in pairs {
+        message.push(' ');
+        message.push_str(key);
+        message.push('=');
+        if value.contains(char::is_whitespace) {
+            message.push('"');
+            message.push_str(value);
+            message.push('"');
+        } else {
+            message.push_str(value);
+        }
+    }
+    message
+}
 
 /// `AndroidLogger` is the implementation of the logger.
 ///
 /// It should not be used from Rust libraries which should only use the facade.
 pub struct AndroidLogger {
     tag: CString,
+    tag_from: Option<Box<Fn(&LogRecord) -> Cow<str> + Sync + Send>>,
     format: Box<Fn(&LogRecord) -> String + Sync + Send>,
+    kv_from: Option<Box<Fn(&LogRecord) -> Vec<(String, String)> + Sync + Send>>,
+    filter: Filter,
+    #[cfg(target_os = "android")]
+    chunk_size: ChunkSize,
+    #[cfg(target_os = "android")]
+    buffer: Buffer,
 }
 
 /// `LogBuilder` acts as builder for initializing the `AndroidLogger`. It can be
@@ -70,7 +218,14 @@ pub struct AndroidLogger {
 /// ```
 pub struct LogBuilder {
     tag: CString,
+    tag_from: Option<Box<Fn(&LogRecord) -> Cow<str> + Sync + Send>>,
     format: Box<Fn(&LogRecord) -> String + Sync + Send>,
+    kv_from: Option<Box<Fn(&LogRecord) -> Vec<(String, String)> + Sync + Send>>,
+    filter: Filter,
+    #[cfg(target_os = "android")]
+    chunk_size: ChunkSize,
+    #[cfg(target_os = "android")]
+    buffer: Buffer,
 }
 
 /// Initializes the global logger with an `AndroidLogger`
@@ -82,6 +237,17 @@ pub fn init<S: Into<String>>(tag: S) -> Result<(), SetLoggerError> {
     AndroidLogger::new(tag).init()
 }
 
+/// Initializes the global logger with an `AndroidLogger`, the first time
+/// it's called.
+///
+/// Unlike `init`, this never fails: many entry points into Android code
+/// (e.g. JNI-reachable functions) have no single well-defined place to call
+/// `init`, and may run more than once, so a second call here is silently a
+/// no-op instead of panicking.
+pub fn init_once<S: Into<String>>(tag: S) {
+    AndroidLogger::new(tag).init_once()
+}
+
 impl AndroidLogger {
     /// Initializes the logger with defaults
     pub fn new<S: Into<String>>(tag: S) -> AndroidLogger {
@@ -95,11 +261,29 @@ impl AndroidLogger {
             Box::new(self)
         })
     }
+
+    /// Initializes the global logger with `self`, the first time it's
+    /// called; later calls are a silent no-op.
+    pub fn init_once(self) {
+        if !INIT_ONCE.swap(true, Ordering::SeqCst) {
+            let _ = self.init();
+        }
+    }
+
+    /// Resolves the tag to use for `record`: the per-record tag from
+    /// `LogBuilder::tag_from` if one is configured, otherwise the fixed tag
+    /// set in `LogBuilder::new`/`AndroidLogger::new`.
+    fn record_tag(&self, record: &LogRecord) -> CString {
+        match self.tag_from {
+            Some(ref tag_from) => CString::new(tag_from(record).into_owned()).unwrap(),
+            None => self.tag.clone(),
+        }
+    }
 }
 
 impl Log for AndroidLogger {
-    fn enabled(&self, _: &LogMetadata) -> bool {
-        true
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        self.filter.enabled(metadata.level(), metadata.target())
     }
 
     fn log(&self, record: &LogRecord) {
@@ -107,9 +291,25 @@ impl Log for AndroidLogger {
             return;
         }
 
-        let format = CString::new((self.format)(record)).unwrap();
+        let message = (self.format)(record);
+        let message = match self.kv_from {
+            Some(ref kv_from) => append_kv(message, &kv_from(record)),
+            None => message,
+        };
+        let tag = self.record_tag(record);
+
+        #[cfg(target_os = "android")]
+        self.write_android(&tag, record.level(), &message);
+
+        #[cfg(not(target_os = "android"))]
+        self.write_host(&tag, record.level(), &message);
+    }
+}
 
-        let prio = match record.level() {
+#[cfg(target_os = "android")]
+impl AndroidLogger {
+    fn write_android(&self, tag: &CString, level: LogLevel, message: &str) {
+        let prio = match level {
             LogLevel::Error => LogPriority::ERROR,
             LogLevel::Warn  => LogPriority::WARN,
             LogLevel::Info  => LogPriority::INFO,
@@ -117,12 +317,38 @@ impl Log for AndroidLogger {
             LogLevel::Trace => LogPriority::VERBOSE,
         };
 
-        unsafe {
-            __android_log_write(prio as _, self.tag.as_ptr(), format.as_ptr());
+        let max_len = self.chunk_size.resolve(tag.as_bytes().len());
+
+        for chunk in split_message(message, max_len) {
+            let chunk = CString::new(chunk).unwrap();
+
+            match self.buffer {
+                Buffer::Main => unsafe {
+                    __android_log_write(prio as _, tag.as_ptr(), chunk.as_ptr());
+                },
+                ref buffer => unsafe {
+                    __android_log_buf_write(buffer.id() as _, prio as _, tag.as_ptr(), chunk.as_ptr());
+                },
+            }
         }
     }
 }
 
+#[cfg(not(target_os = "android"))]
+impl AndroidLogger {
+    fn write_host(&self, tag: &CString, level: LogLevel, message: &str) {
+        let level = match level {
+            LogLevel::Error => "E",
+            LogLevel::Warn  => "W",
+            LogLevel::Info  => "I",
+            LogLevel::Debug => "D",
+            LogLevel::Trace => "V",
+        };
+
+        eprintln!("{} {}: {}", level, tag.to_string_lossy(), message);
+    }
+}
+
 impl LogBuilder {
     /// Initializes the builder with defaults
     pub fn new<S: Into<String>>(tag: S) -> LogBuilder {
@@ -133,6 +359,13 @@ impl LogBuilder {
                         record.location().module_path(),
                         record.args())
             }),
+            tag_from: None,
+            kv_from: None,
+            filter: Filter::default(),
+            #[cfg(target_os = "android")]
+            chunk_size: ChunkSize::Auto,
+            #[cfg(target_os = "android")]
+            buffer: Buffer::Main,
         }
     }
 
@@ -144,16 +377,110 @@ impl LogBuilder {
         self
     }
 
+    /// Derives the logcat tag per record instead of using the fixed tag
+    /// passed to `LogBuilder::new`, so different modules (or whatever `f`
+    /// chooses) surface as different logcat tags for `logcat -s TAG`.
+    pub fn tag_from<F: 'static>(&mut self, f: F) -> &mut Self
+        where F: Fn(&LogRecord) -> Cow<str> + Sync + Send
+    {
+        self.tag_from = Some(Box::new(f));
+        self
+    }
+
+    /// Shorthand for `tag_from` that derives the tag from each record's
+    /// module path.
+    pub fn tag_from_module(&mut self) -> &mut Self {
+        self.tag_from(|record: &LogRecord| Cow::Borrowed(record.location().module_path()))
+    }
+
+    /// Appends `key=value` pairs returned by `f` to the formatted message,
+    /// quoting values that contain whitespace, so tooling has something
+    /// machine-readable to grep while the human-readable message stays
+    /// intact.
+    pub fn kv_from<F: 'static>(&mut self, f: F) -> &mut Self
+        where F: Fn(&LogRecord) -> Vec<(String, String)> + Sync + Send
+    {
+        self.kv_from = Some(Box::new(f));
+        self
+    }
+
+    /// Parses `spec` as an env_logger-style filter string, e.g.
+    /// `"debug,hello::crate=trace,noisy_mod=off"`, and uses it to decide
+    /// which records are logged (to liblog on Android, to stderr elsewhere).
+    ///
+    /// The spec is a comma-separated list of `module::path=level`
+    /// directives; a bare `level` with no module path sets the default
+    /// level for modules that match no directive. The most specific
+    /// (longest) matching module path wins.
+    pub fn filter(&mut self, spec: &str) -> &mut Self {
+        self.filter = Filter::new(spec);
+        self
+    }
+
+    /// Sets how over-length messages are split across multiple `liblog`
+    /// writes, since liblog silently truncates any single entry at around
+    /// 4000 bytes.
+    ///
+    /// Defaults to `ChunkSize::Auto`, which computes a safe budget from the
+    /// tag length. Callers who know their messages are always short can
+    /// pass `ChunkSize::Disabled` to skip the splitting work entirely.
+    #[cfg(target_os = "android")]
+    pub fn chunk_size(&mut self, chunk_size: ChunkSize) -> &mut Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Sets the logcat buffer records are written to, e.g. `Buffer::System`
+    /// so system-level components show up under `logcat -b system` instead
+    /// of the default main buffer.
+    #[cfg(target_os = "android")]
+    pub fn buffer(&mut self, buffer: Buffer) -> &mut Self {
+        self.buffer = buffer;
+        self
+    }
+
     /// Builds an `AndroidLogger` and initializes the global logger
     pub fn init(self) -> Result<(), SetLoggerError> {
         self.build().init()
     }
 
+    /// Builds an `AndroidLogger` and initializes the global logger, the
+    /// first time it's called; later calls are a silent no-op.
+    pub fn init_once(self) {
+        self.build().init_once()
+    }
+
     /// Builds an `AndroidLogger`
     pub fn build(self) -> AndroidLogger {
         AndroidLogger {
             tag: self.tag,
+            tag_from: self.tag_from,
             format: self.format,
+            kv_from: self.kv_from,
+            filter: host_filter(self.filter),
+            #[cfg(target_os = "android")]
+            chunk_size: self.chunk_size,
+            #[cfg(target_os = "android")]
+            buffer: self.buffer,
+        }
+    }
+}
+
+/// On Android, the filter is used as configured. Off Android, an unset
+/// filter falls back to the `RUST_LOG` environment variable, so the same
+/// binary can be tuned the way `env_logger`-based tools are.
+#[cfg(target_os = "android")]
+fn host_filter(filter: Filter) -> Filter {
+    filter
+}
+
+#[cfg(not(target_os = "android"))]
+fn host_filter(filter: Filter) -> Filter {
+    if filter.is_unset() {
+        if let Ok(spec) = env::var("RUST_LOG") {
+            return Filter::new(&spec);
         }
     }
+
+    filter
 }